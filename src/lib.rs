@@ -32,7 +32,12 @@ pub enum RunningAs {
 use RunningAs::*;
 
 /// Check getuid() and geteuid() to learn about the configuration this program
-/// is running under
+/// is running under.
+///
+/// The result describes privilege relative to root; when an [`Escalate`]
+/// targets a non-root user, `Root` means "privileged enough to drop to the
+/// target" and the escalation path still performs the `setuid`/`setgid` to the
+/// requested identity.
 fn check() -> RunningAs {
     let uid = unsafe { libc::getuid() };
     let euid = unsafe { libc::geteuid() };
@@ -55,14 +60,652 @@ pub fn running_as_suid() -> bool {
     check() == RunningAs::Suid
 }
 
+/// The privilege-escalation program backing an [`Escalate`] builder.
+///
+/// Remembering the backend kind lets later code branch on behaviour (e.g. the
+/// `pkexec` env-prefixing in `collect_envs`) instead of string-comparing the
+/// wrapper name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Doas,
+    Sudo,
+    Gsudo,
+    Pkexec,
+}
+
+impl Backend {
+    /// Order `detect()` probes `$PATH` in: `doas` before `sudo`, `sudo` before
+    /// the Windows-ish `gsudo`, and polkit's `pkexec` last.
+    const PRIORITY: [Backend; 4] = [Backend::Doas, Backend::Sudo, Backend::Gsudo, Backend::Pkexec];
+
+    /// The program name looked up on `$PATH`.
+    fn program(self) -> &'static str {
+        match self {
+            Backend::Doas => "doas",
+            Backend::Sudo => "sudo",
+            Backend::Gsudo => "gsudo",
+            Backend::Pkexec => "pkexec",
+        }
+    }
+
+    /// Best-effort classification of a wrapper name set by hand; anything
+    /// unrecognised is treated as `sudo`-compatible.
+    fn from_wrapper(wrapper: &str) -> Backend {
+        match wrapper {
+            "doas" => Backend::Doas,
+            "gsudo" => Backend::Gsudo,
+            "pkexec" => Backend::Pkexec,
+            _ => Backend::Sudo,
+        }
+    }
+
+    /// The flag that makes the backend fail fast instead of prompting for a
+    /// password when cached credentials are missing.
+    fn non_interactive_flag(self) -> &'static str {
+        match self {
+            Backend::Pkexec => "--disable-internal-agent",
+            // sudo, doas and gsudo all spell this `-n`.
+            _ => "-n",
+        }
+    }
+
+    /// The flag that asks the backend to run the child under its own pty, or
+    /// `None` for backends that lack one (where we allocate a pty in-process).
+    fn pty_flag(self) -> Option<&'static str> {
+        match self {
+            // `sudo --pty` (cf. `su -P`); doas, gsudo and pkexec have none.
+            Backend::Sudo => Some("--pty"),
+            _ => None,
+        }
+    }
+}
+
+/// Everything that can go wrong while escalating privileges.
+///
+/// Returned instead of `Box<dyn Error>` (and instead of the old `panic!`/
+/// `std::process::exit` paths) so a library consumer can recover — fall back to
+/// another backend when one is missing, or skip a privileged step when
+/// authentication is unavailable.
+#[derive(Debug)]
+pub enum EscalateError {
+    /// The configured wrapper program was not found on `$PATH`.
+    WrapperNotFound(String),
+    /// The wrapper was found but the child process could not be spawned.
+    SpawnFailed(std::io::Error),
+    /// Dropping to the target uid/gid (`setgid`/`initgroups`/`setuid`) failed,
+    /// so the process did *not* become the requested identity.
+    PrivilegeDropFailed(std::io::Error),
+    /// A non-interactive escalation needed a password that was not cached, so
+    /// the backend exited rather than prompting. Lets a CI/test harness catch
+    /// "needs password" and skip gracefully instead of stalling on a TTY read.
+    AuthenticationFailed,
+    /// The wrapper ran but exited with the given non-zero status.
+    ChildExited(i32),
+    /// A pre-flight [`with_policy`](Escalate::with_policy) check refused the
+    /// escalation before the wrapper was spawned. The string describes the
+    /// denied `(user, command, target)` request.
+    PolicyDenied(String),
+}
+
+impl std::fmt::Display for EscalateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EscalateError::WrapperNotFound(w) => {
+                write!(f, "escalation wrapper {:?} not found on $PATH", w)
+            }
+            EscalateError::SpawnFailed(e) => write!(f, "failed to spawn escalation wrapper: {}", e),
+            EscalateError::PrivilegeDropFailed(e) => {
+                write!(f, "failed to drop to the target identity: {}", e)
+            }
+            EscalateError::AuthenticationFailed => {
+                write!(f, "escalation requires authentication but ran non-interactively")
+            }
+            EscalateError::ChildExited(code) => {
+                write!(f, "escalated process exited with status {}", code)
+            }
+            EscalateError::PolicyDenied(what) => write!(f, "escalation policy denied: {}", what),
+        }
+    }
+}
+
+impl Error for EscalateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EscalateError::SpawnFailed(e) | EscalateError::PrivilegeDropFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for EscalateError {
+    fn from(e: std::io::Error) -> Self {
+        EscalateError::SpawnFailed(e)
+    }
+}
+
+/// Find `program` on `$PATH`, returning its path in the first `$PATH`
+/// directory that holds a regular file by that name. The execute permission is
+/// not checked.
+fn which_on_path(program: &str) -> Option<std::path::PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
+/// Resolve a user name to its `(uid, primary gid)` via `getpwnam_r`.
+fn passwd_for_user(name: &str) -> Option<(libc::uid_t, libc::gid_t)> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    loop {
+        let rc = unsafe {
+            libc::getpwnam_r(
+                cname.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        return Some((pwd.pw_uid, pwd.pw_gid));
+    }
+}
+
+/// Run `command` attached to a freshly allocated pseudo-terminal and proxy the
+/// parent's terminal to it, returning the child's exit status.
+///
+/// Used for backends that have no pty flag of their own (`doas`, `gsudo`,
+/// `pkexec`): we `openpty`, hand the slave to the child as its controlling
+/// terminal, and shuttle bytes between the parent's stdin/stdout and the
+/// master while forwarding `SIGWINCH` so window resizes propagate.
+fn run_on_pty(mut command: Command) -> Result<std::process::ExitStatus, EscalateError> {
+    use std::os::fd::FromRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let mut master: libc::c_int = 0;
+    let mut slave: libc::c_int = 0;
+    // Seed the child's window size from the parent's so the TUI starts sized
+    // correctly; SIGWINCH keeps it in sync afterwards.
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    let have_winsize =
+        unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut winsize) } == 0;
+    let ws = have_winsize.then_some(&winsize);
+    let rc = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            ws.map_or(std::ptr::null(), |w| w as *const _),
+        )
+    };
+    if rc != 0 {
+        return Err(EscalateError::SpawnFailed(std::io::Error::last_os_error()));
+    }
+
+    // Keep the master end out of the child: mark it close-on-exec so the
+    // escalated process does not inherit the parent side of its own pty (a
+    // canonical `forkpty` closes master in the child).
+    unsafe { libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC) };
+
+    // The child gets the slave as stdio and makes it its controlling terminal.
+    let slave_for_child = slave;
+    unsafe {
+        command
+            .stdin(std::process::Stdio::from_raw_fd(libc::dup(slave)))
+            .stdout(std::process::Stdio::from_raw_fd(libc::dup(slave)))
+            .stderr(std::process::Stdio::from_raw_fd(libc::dup(slave)))
+            .pre_exec(move || {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if libc::ioctl(slave_for_child, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+    }
+
+    let mut child = command.spawn().map_err(EscalateError::SpawnFailed)?;
+    // Parent no longer needs the slave end.
+    unsafe { libc::close(slave) };
+
+    // Put the parent terminal into raw mode so keystrokes reach the child
+    // unbuffered and unechoed (the child's own tty handles echo). The guard
+    // restores the original settings on every return path, including errors.
+    let _raw = RawModeGuard::enable(libc::STDIN_FILENO);
+
+    // Relay the parent terminal's dimensions to the pty, now and on every
+    // subsequent SIGWINCH.
+    propagate_winsize(master);
+    unsafe {
+        libc::signal(
+            libc::SIGWINCH,
+            handle_sigwinch as *const () as libc::sighandler_t,
+        )
+    };
+
+    // Shuttle bytes both ways until the child closes the pty. `poll` keeps the
+    // parent responsive to input while the child writes.
+    let mut fds = [
+        libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 },
+        libc::pollfd { fd: master, events: libc::POLLIN, revents: 0 },
+    ];
+    let mut buf = [0u8; 4096];
+    loop {
+        if WINCH_PENDING.swap(false, std::sync::atomic::Ordering::SeqCst) {
+            propagate_winsize(master);
+        }
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            break;
+        }
+        if fds[0].revents & libc::POLLIN != 0 {
+            let r = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if r <= 0 {
+                // The parent's stdin closed. Stop forwarding it (a negative fd
+                // is ignored by `poll`) but keep relaying the master so a child
+                // that is still running is not torn down.
+                fds[0].fd = -1;
+            } else {
+                unsafe { libc::write(master, buf.as_ptr() as *const _, r as usize) };
+            }
+        }
+        if fds[1].revents & (libc::POLLIN | libc::POLLHUP) != 0 {
+            let r = unsafe { libc::read(master, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if r <= 0 {
+                // The child closed its end of the pty: it has exited.
+                break;
+            }
+            unsafe { libc::write(libc::STDOUT_FILENO, buf.as_ptr() as *const _, r as usize) };
+        }
+    }
+
+    unsafe { libc::close(master) };
+    Ok(child.wait()?)
+}
+
+/// Restores the terminal's original line discipline when dropped.
+///
+/// `enable` switches `fd` into raw mode (via `cfmakeraw`) and remembers the
+/// previous `termios`; `Drop` puts it back, so the parent terminal is left
+/// cooked again however `run_on_pty` returns. A non-tty stdin (where
+/// `tcgetattr` fails) is left untouched.
+struct RawModeGuard {
+    fd: libc::c_int,
+    original: Option<libc::termios>,
+}
+
+impl RawModeGuard {
+    fn enable(fd: libc::c_int) -> Self {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return RawModeGuard { fd, original: None };
+        }
+        let mut raw = original;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            libc::tcsetattr(fd, libc::TCSANOW, &raw);
+        }
+        RawModeGuard { fd, original: Some(original) }
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        if let Some(original) = self.original {
+            unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &original) };
+        }
+    }
+}
+
+/// Set by the `SIGWINCH` handler; drained by the proxy loop, which does the
+/// actual (non-async-signal-safe) `ioctl` work outside the handler.
+static WINCH_PENDING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// `SIGWINCH` handler: just flag the resize; the proxy loop performs it.
+extern "C" fn handle_sigwinch(_sig: libc::c_int) {
+    WINCH_PENDING.store(true, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Copy the parent terminal's window size onto the pty master.
+fn propagate_winsize(master: libc::c_int) {
+    let mut winsize: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut winsize) } == 0 {
+        unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &winsize) };
+    }
+}
+
+/// Resolve a group name to its gid via `getgrnam_r`.
+fn gid_for_group(name: &str) -> Option<libc::gid_t> {
+    let cname = std::ffi::CString::new(name).ok()?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    loop {
+        let rc = unsafe {
+            libc::getgrnam_r(
+                cname.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        return Some(grp.gr_gid);
+    }
+}
+
+/// Resolve a uid to its login name via `getpwuid_r`.
+fn username_for_uid(uid: libc::uid_t) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    loop {
+        let rc = unsafe {
+            libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        };
+        if rc == libc::ERANGE {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+        return name.to_str().ok().map(str::to_string);
+    }
+}
+
+/// Minimal JSON value, enough to read a policy file without pulling in a
+/// `serde`/`serde_json` dependency (the crate otherwise needs only libc,
+/// wildmatch and tracing).
+enum Json {
+    // `null`, booleans and numbers are accepted and skipped but never
+    // inspected, so they carry no payload.
+    Null,
+    Bool,
+    Number,
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Parse a whole JSON document, rejecting trailing characters.
+    fn parse(text: &str) -> Result<Json, String> {
+        let bytes = text.as_bytes();
+        let mut pos = 0;
+        let value = parse_value(bytes, &mut pos)?;
+        skip_ws(bytes, &mut pos);
+        if pos != bytes.len() {
+            return Err("trailing characters after JSON value".to_string());
+        }
+        Ok(value)
+    }
+}
+
+fn skip_ws(bytes: &[u8], pos: &mut usize) {
+    while matches!(bytes.get(*pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+        *pos += 1;
+    }
+}
+
+fn parse_value(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    skip_ws(bytes, pos);
+    match bytes.get(*pos) {
+        Some(b'{') => parse_object(bytes, pos),
+        Some(b'[') => parse_array(bytes, pos),
+        Some(b'"') => Ok(Json::Str(parse_string(bytes, pos)?)),
+        Some(b't' | b'f') => parse_bool(bytes, pos),
+        Some(b'n') => parse_null(bytes, pos),
+        Some(_) => parse_number(bytes, pos),
+        None => Err("unexpected end of JSON".to_string()),
+    }
+}
+
+fn parse_object(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    *pos += 1; // consume '{'
+    let mut fields = Vec::new();
+    skip_ws(bytes, pos);
+    if bytes.get(*pos) == Some(&b'}') {
+        *pos += 1;
+        return Ok(Json::Object(fields));
+    }
+    loop {
+        skip_ws(bytes, pos);
+        if bytes.get(*pos) != Some(&b'"') {
+            return Err("expected string key in object".to_string());
+        }
+        let key = parse_string(bytes, pos)?;
+        skip_ws(bytes, pos);
+        if bytes.get(*pos) != Some(&b':') {
+            return Err("expected ':' after object key".to_string());
+        }
+        *pos += 1;
+        let value = parse_value(bytes, pos)?;
+        fields.push((key, value));
+        skip_ws(bytes, pos);
+        match bytes.get(*pos) {
+            Some(b',') => *pos += 1,
+            Some(b'}') => {
+                *pos += 1;
+                return Ok(Json::Object(fields));
+            }
+            _ => return Err("expected ',' or '}' in object".to_string()),
+        }
+    }
+}
+
+fn parse_array(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    *pos += 1; // consume '['
+    let mut items = Vec::new();
+    skip_ws(bytes, pos);
+    if bytes.get(*pos) == Some(&b']') {
+        *pos += 1;
+        return Ok(Json::Array(items));
+    }
+    loop {
+        items.push(parse_value(bytes, pos)?);
+        skip_ws(bytes, pos);
+        match bytes.get(*pos) {
+            Some(b',') => *pos += 1,
+            Some(b']') => {
+                *pos += 1;
+                return Ok(Json::Array(items));
+            }
+            _ => return Err("expected ',' or ']' in array".to_string()),
+        }
+    }
+}
+
+fn parse_string(bytes: &[u8], pos: &mut usize) -> Result<String, String> {
+    *pos += 1; // consume opening '"'
+    // Accumulate raw bytes so multi-byte UTF-8 passes through untouched; only
+    // ASCII escapes are special-cased.
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let b = *bytes.get(*pos).ok_or("unterminated string")?;
+        *pos += 1;
+        match b {
+            b'"' => return String::from_utf8(out).map_err(|_| "invalid UTF-8 in string".to_string()),
+            b'\\' => {
+                let esc = *bytes.get(*pos).ok_or("unterminated escape")?;
+                *pos += 1;
+                match esc {
+                    b'"' => out.push(b'"'),
+                    b'\\' => out.push(b'\\'),
+                    b'/' => out.push(b'/'),
+                    b'b' => out.push(0x08),
+                    b'f' => out.push(0x0C),
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'u' => {
+                        let hex = bytes.get(*pos..*pos + 4).ok_or("truncated \\u escape")?;
+                        let code = std::str::from_utf8(hex)
+                            .ok()
+                            .and_then(|h| u32::from_str_radix(h, 16).ok())
+                            .ok_or("invalid \\u escape")?;
+                        let ch = char::from_u32(code).ok_or("invalid code point")?;
+                        let mut enc = [0u8; 4];
+                        out.extend_from_slice(ch.encode_utf8(&mut enc).as_bytes());
+                        *pos += 4;
+                    }
+                    _ => return Err("invalid escape sequence".to_string()),
+                }
+            }
+            _ => out.push(b),
+        }
+    }
+}
+
+fn parse_bool(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    if bytes[*pos..].starts_with(b"true") {
+        *pos += 4;
+        Ok(Json::Bool)
+    } else if bytes[*pos..].starts_with(b"false") {
+        *pos += 5;
+        Ok(Json::Bool)
+    } else {
+        Err("invalid literal".to_string())
+    }
+}
+
+fn parse_null(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    if bytes[*pos..].starts_with(b"null") {
+        *pos += 4;
+        Ok(Json::Null)
+    } else {
+        Err("invalid literal".to_string())
+    }
+}
+
+fn parse_number(bytes: &[u8], pos: &mut usize) -> Result<Json, String> {
+    let start = *pos;
+    while matches!(
+        bytes.get(*pos),
+        Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')
+    ) {
+        *pos += 1;
+    }
+    std::str::from_utf8(&bytes[start..*pos])
+        .ok()
+        .filter(|s| s.parse::<f64>().is_ok())
+        .map(|_| Json::Number)
+        .ok_or_else(|| "invalid number".to_string())
+}
+
+/// One rule from a [`with_policy`](Escalate::with_policy) JSON file: who may
+/// escalate (`user`), the target they may become (`runas`) and a glob the
+/// invoking executable must match (`command_glob`). A missing field defaults to
+/// `"*"` (match anything); the rule list is evaluated default-deny.
+#[derive(Debug)]
+struct PolicyRule {
+    user: String,
+    runas: String,
+    command_glob: String,
+}
+
+/// A parsed escalation policy: an ordered list of permit rules with a
+/// default-deny fallthrough.
+struct Policy {
+    rules: Vec<PolicyRule>,
+}
+
+impl Policy {
+    /// Load a policy from a JSON array of `{ user, runas, command_glob }`
+    /// objects.
+    fn load(path: &std::path::Path) -> Result<Policy, EscalateError> {
+        let invalid = |msg: String| {
+            EscalateError::SpawnFailed(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+        };
+        let text = std::fs::read_to_string(path).map_err(EscalateError::SpawnFailed)?;
+        let value = Json::parse(&text).map_err(invalid)?;
+        let entries = value
+            .as_array()
+            .ok_or_else(|| invalid("policy file must be a JSON array of rules".to_string()))?;
+        let field = |entry: &Json, key: &str| {
+            entry
+                .get(key)
+                .and_then(Json::as_str)
+                .unwrap_or("*")
+                .to_string()
+        };
+        let rules = entries
+            .iter()
+            .map(|entry| PolicyRule {
+                user: field(entry, "user"),
+                runas: field(entry, "runas"),
+                command_glob: field(entry, "command_glob"),
+            })
+            .collect();
+        Ok(Policy { rules })
+    }
+}
+
 pub struct Escalate {
     wrapper: String,
+    backend: Backend,
+    non_interactive: bool,
+    as_user: Option<String>,
+    as_group: Option<String>,
+    pty: bool,
+    policy_path: Option<std::path::PathBuf>,
 }
 
 impl Default for Escalate {
     fn default() -> Self {
         Escalate {
             wrapper: "sudo".to_string(),
+            backend: Backend::Sudo,
+            non_interactive: false,
+            as_user: None,
+            as_group: None,
+            pty: false,
+            policy_path: None,
         }
     }
 }
@@ -72,16 +715,208 @@ impl Escalate {
         Default::default()
     }
 
-    fn wrapper(&mut self, wrapper: &str) -> &mut Self {
+    /// Probe `$PATH` for a supported backend and return a builder configured
+    /// with the first one installed.
+    ///
+    /// Backends are tried in the order `doas`, `sudo`, `gsudo`, `pkexec`, so a
+    /// single binary runs unmodified across Debian, the BSDs and polkit-only
+    /// desktops. Falls back to `sudo` when none are found — escalation then
+    /// returns [`EscalateError::WrapperNotFound`] when attempted rather than
+    /// failing here.
+    pub fn detect() -> Self {
+        for backend in Backend::PRIORITY {
+            if which_on_path(backend.program()).is_some() {
+                return Escalate {
+                    wrapper: backend.program().to_string(),
+                    backend,
+                    ..Default::default()
+                };
+            }
+        }
+        Default::default()
+    }
+
+    /// Override the wrapper program used to escalate (e.g. `"doas"`).
+    ///
+    /// The backend kind is re-derived from the name so behaviour that keys off
+    /// it (such as the `pkexec` env handling) stays consistent.
+    pub fn wrapper(&mut self, wrapper: &str) -> &mut Self {
         self.wrapper = wrapper.to_string();
+        self.backend = Backend::from_wrapper(wrapper);
         self
     }
 
+    /// Fail fast instead of blocking on a password prompt.
+    ///
+    /// Appends the backend's no-prompt flag (`sudo -n`, `doas -n`,
+    /// `pkexec --disable-internal-agent`) so that, when cached credentials are
+    /// absent, the child exits immediately. `collect_envs` then surfaces that
+    /// as [`EscalateError::AuthenticationFailed`] rather than terminating the
+    /// process, letting a headless/CI caller recover.
+    pub fn non_interactive(&mut self) -> &mut Self {
+        self.non_interactive = true;
+        self
+    }
+
+    /// Become `user` instead of root.
+    ///
+    /// In the re-exec path this maps to `sudo -u NAME` / `doas -u NAME`
+    /// (`pkexec --user NAME`); in the `Suid`/root path the name is resolved via
+    /// `getpwnam_r` and used with `setuid`.
+    pub fn as_user(&mut self, user: &str) -> &mut Self {
+        self.as_user = Some(user.to_string());
+        self
+    }
+
+    /// Run with `group` as the primary group of the escalated process.
+    ///
+    /// In the re-exec path this maps to `sudo -g GROUP` (ignored for backends
+    /// such as `doas` that have no group flag); in the `Suid`/root path the
+    /// name is resolved via `getgrnam_r` and used with `setgid`.
+    pub fn as_group(&mut self, group: &str) -> &mut Self {
+        self.as_group = Some(group.to_string());
+        self
+    }
+
+    /// Allocate a pseudo-terminal for the escalated child.
+    ///
+    /// Backends with a native flag get it passed through (`sudo --pty`);
+    /// for the rest we allocate a pty in-process, run the child on its slave
+    /// and proxy the parent terminal to it, forwarding `SIGWINCH`. This fixes
+    /// job-control and signal-delivery issues for programs that read a password
+    /// or draw a TUI behind the wrapper.
+    pub fn pty(&mut self) -> &mut Self {
+        self.pty = true;
+        self
+    }
+
+    /// Gate escalation on a JSON policy file, checked before the wrapper runs.
+    ///
+    /// The file is a JSON array of `{ user, runas, command_glob }` rules. At
+    /// escalation time the current `getuid()` (resolved to a name), the target
+    /// user and `current_exe()` are matched against each rule in order —
+    /// `command_glob` uses the same `wildmatch` globbing as env selection, and
+    /// a missing field means "any". On a miss the fallthrough is default-deny
+    /// and escalation fails with [`EscalateError::PolicyDenied`] instead of
+    /// letting the backend prompt and then reject, giving a tool a way to
+    /// self-restrict independently of the system sudoers configuration.
+    ///
+    /// The file is read lazily when escalation is attempted, so a bad path
+    /// surfaces as an error from `escalate_if_needed` rather than here.
+    pub fn with_policy(&mut self, path: impl Into<std::path::PathBuf>) -> &mut Self {
+        self.policy_path = Some(path.into());
+        self
+    }
+
+    /// Enforce the configured policy (if any) before spawning the wrapper.
+    fn enforce_policy(&self) -> Result<(), EscalateError> {
+        let Some(path) = &self.policy_path else {
+            return Ok(());
+        };
+        let policy = Policy::load(path)?;
+
+        let uid = unsafe { libc::getuid() };
+        let user = username_for_uid(uid).unwrap_or_else(|| uid.to_string());
+        let runas = self.as_user.as_deref().unwrap_or("root").to_string();
+        let command = std::env::current_exe()
+            .ok()
+            .and_then(|p| p.to_str().map(str::to_string))
+            .unwrap_or_default();
+
+        for rule in &policy.rules {
+            let user_ok = rule.user == "*" || rule.user == user;
+            let runas_ok = rule.runas == "*" || rule.runas == runas;
+            let command_ok = wildmatch::WildMatch::new(&rule.command_glob).matches(&command);
+            if user_ok && runas_ok && command_ok {
+                tracing::trace!("escalation permitted by policy rule {:?}", rule);
+                return Ok(());
+            }
+        }
+
+        Err(EscalateError::PolicyDenied(format!(
+            "user {} may not run {} as {}",
+            user, command, runas
+        )))
+    }
+
+    /// Resolve the configured target to a concrete `(uid, gid)` pair for the
+    /// `Suid`/root drop path. Defaults to root (`0, 0`); a named user
+    /// contributes its primary gid unless a group is given explicitly.
+    fn resolve_target(&self) -> Result<(libc::uid_t, libc::gid_t), EscalateError> {
+        let (mut uid, mut gid) = (0, 0);
+        if let Some(user) = &self.as_user {
+            let (u, g) = passwd_for_user(user).ok_or_else(|| {
+                EscalateError::SpawnFailed(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("unknown user: {}", user),
+                ))
+            })?;
+            uid = u;
+            gid = g;
+        }
+        if let Some(group) = &self.as_group {
+            gid = gid_for_group(group).ok_or_else(|| {
+                EscalateError::SpawnFailed(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("unknown group: {}", group),
+                ))
+            })?;
+        }
+        Ok((uid, gid))
+    }
+
+    /// Drop the current process to `(uid, gid)` in the `Suid`/root branch.
+    ///
+    /// Each syscall's return value is checked: a silent failure here would
+    /// leave the process running as root while the caller is told it became the
+    /// target user. For a named user the supplementary group list is also
+    /// initialised via `initgroups`, so the process does not retain root's
+    /// groups. Order matters — groups and gid must be set while still
+    /// privileged, before `setuid` surrenders it.
+    fn drop_to(&self, uid: libc::uid_t, gid: libc::gid_t) -> Result<(), EscalateError> {
+        tracing::trace!("setgid({}); setuid({})", gid, uid);
+        let last_err = || EscalateError::PrivilegeDropFailed(std::io::Error::last_os_error());
+        unsafe {
+            if let Some(user) = &self.as_user {
+                if let Ok(cname) = std::ffi::CString::new(user.as_str()) {
+                    if libc::initgroups(cname.as_ptr(), gid as _) != 0 {
+                        return Err(last_err());
+                    }
+                }
+            }
+            if libc::setgid(gid) != 0 {
+                return Err(last_err());
+            }
+            if libc::setuid(uid) != 0 {
+                return Err(last_err());
+            }
+        }
+        Ok(())
+    }
+
+    /// Probe whether the backend can authenticate without prompting.
+    ///
+    /// Runs the wrapper's no-prompt flag against a no-op command (`sudo -n
+    /// true`, `doas -n true`, `pkexec --disable-internal-agent true`) with all
+    /// stdio discarded. Success means cached/passwordless credentials are
+    /// available, so a non-zero exit from the real escalation must be the
+    /// child's own failure rather than a missing password.
+    fn auth_available(&self) -> bool {
+        let status = Command::new(&self.wrapper)
+            .arg(self.backend.non_interactive_flag())
+            .arg("true")
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        matches!(status, Ok(code) if code.success())
+    }
+
     /// Escalate privileges while maintaining RUST_BACKTRACE and selected
     /// environment variables (or none).
     ///
     /// Activates SUID privileges when available.
-    fn with_env(&self, prefixes: &[&str]) -> Result<RunningAs, Box<dyn Error>> {
+    fn with_env(&self, prefixes: &[&str]) -> Result<RunningAs, EscalateError> {
         self.collect_envs(prefixes, false)
     }
 
@@ -90,26 +925,36 @@ impl Escalate {
     /// all environment variables (mimics `sudo -E`)
     ///
     /// Activates SUID privileges when available.
-    fn with_env_wildcards(&self, wildcards: &[&str]) -> Result<RunningAs, Box<dyn Error>> {
+    fn with_env_wildcards(&self, wildcards: &[&str]) -> Result<RunningAs, EscalateError> {
         self.collect_envs(wildcards, true)
     }
 
-    fn collect_envs(&self, patterns: &[&str], is_glob: bool) -> Result<RunningAs, Box<dyn Error>> {
+    fn collect_envs(&self, patterns: &[&str], is_glob: bool) -> Result<RunningAs, EscalateError> {
         let current = check();
         tracing::trace!("Running as {:?}", current);
         match current {
-            Root => {
+            // Already at the target identity: root with no other user or
+            // group requested. Anything else (a named target, or a SUID binary
+            // that still needs to claim its ids) drops straight to the resolved
+            // uid/gid without a re-exec.
+            Root if self.as_user.is_none() && self.as_group.is_none() => {
                 tracing::trace!("already running as Root");
                 return Ok(current);
             }
-            Suid => {
-                tracing::trace!("setuid(0)");
-                unsafe {
-                    libc::setuid(0);
-                }
+            Root | Suid => {
+                // Dropping in-place to a target id is just as privileged as a
+                // re-exec, so it is policed too: enforce before resolving or
+                // changing any id.
+                self.enforce_policy()?;
+                let (uid, gid) = self.resolve_target()?;
+                self.drop_to(uid, gid)?;
                 return Ok(current);
             }
             User => {
+                // Self-restrict before spawning a wrapper: a policy miss must
+                // deny the escalation up front rather than after the backend
+                // has prompted.
+                self.enforce_policy()?;
                 tracing::debug!("Escalating privileges");
             }
         }
@@ -121,8 +966,48 @@ impl Escalate {
         {
             args[0] = absolute_path;
         }
+        // Fail with a recoverable error rather than panicking inside
+        // `spawn().expect(...)` when the wrapper is not installed, so a caller
+        // can fall back to a different backend.
+        if which_on_path(&self.wrapper).is_none() {
+            return Err(EscalateError::WrapperNotFound(self.wrapper.clone()));
+        }
+
         let mut command: Command = Command::new(&self.wrapper);
 
+        if self.non_interactive {
+            command.arg(self.backend.non_interactive_flag());
+        }
+
+        // Backends with a native pty flag get it here; the others are run on an
+        // in-process pty below (see `run_on_pty`).
+        if self.pty {
+            if let Some(flag) = self.backend.pty_flag() {
+                command.arg(flag);
+            }
+        }
+
+        if let Some(user) = &self.as_user {
+            match self.backend {
+                Backend::Pkexec => command.arg("--user").arg(user),
+                // sudo, doas and gsudo all spell the target user `-u NAME`.
+                _ => command.arg("-u").arg(user),
+            };
+        }
+
+        if let Some(group) = &self.as_group {
+            // Only the sudo family understands a separate runas group; doas and
+            // pkexec have no equivalent flag.
+            match self.backend {
+                Backend::Sudo | Backend::Gsudo => {
+                    command.arg("-g").arg(group);
+                }
+                Backend::Doas | Backend::Pkexec => {
+                    tracing::warn!("{} has no runas-group flag; ignoring as_group", self.wrapper);
+                }
+            }
+        }
+
         // Always propagate RUST_BACKTRACE
         if let Ok(trace) = std::env::var("RUST_BACKTRACE") {
             let value = match &*trace.to_lowercase() {
@@ -146,7 +1031,7 @@ impl Escalate {
 
         if !patterns.is_empty() {
             // Only add env for pkexec if we're passing any additional env vars
-            if self.wrapper == "pkexec" {
+            if self.backend == Backend::Pkexec {
                 tracing::trace!(
                     "Prefixing `env` to pkexec command to pass additional environment variables! \
                      This may break pkexec system policies."
@@ -163,7 +1048,7 @@ impl Escalate {
                     }
                 }) {
                     tracing::trace!("propagating {}={}", name, value);
-                    if self.wrapper == "pkexec" {
+                    if self.backend == Backend::Pkexec {
                         command.arg(format!("{}={}", name, value));
                     }
                     command.env(name, value);
@@ -171,22 +1056,41 @@ impl Escalate {
             }
         }
 
-        let mut child = command.args(args).spawn().expect("failed to execute child");
-
-        let ecode = child.wait().expect("failed to wait on child");
+        command.args(args);
 
-        if ecode.success() == false {
-            std::process::exit(ecode.code().unwrap_or(1));
+        // When a pty was requested but the backend has no flag of its own, run
+        // the child on an in-process pty and proxy the parent's terminal.
+        let ecode = if self.pty && self.backend.pty_flag().is_none() {
+            run_on_pty(command)?
         } else {
+            let mut child = command.spawn().map_err(EscalateError::SpawnFailed)?;
+            child.wait().map_err(EscalateError::SpawnFailed)?
+        };
+
+        if ecode.success() {
             std::process::exit(0);
         }
+
+        // In non-interactive mode a non-zero exit is ambiguous: the backend may
+        // have bailed out because it could not authenticate, or authentication
+        // succeeded and the re-exec'd program itself exited non-zero. Probe the
+        // wrapper with a no-op to tell the two apart, and only report
+        // `AuthenticationFailed` when credentials really are unavailable.
+        if self.non_interactive && !self.auth_available() {
+            tracing::debug!("non-interactive escalation failed: authentication required");
+            return Err(EscalateError::AuthenticationFailed);
+        }
+
+        // The wrapper itself failed (e.g. the user was denied). Surface the
+        // status so a consumer can decide what to do rather than exiting.
+        Err(EscalateError::ChildExited(ecode.code().unwrap_or(1)))
     }
 
     /// Restart your program with root privileges if the user is not privileged
     /// enough.
     ///
     /// Activates SUID privileges when available
-    pub fn escalate_if_needed(&self) -> Result<RunningAs, Box<dyn Error>> {
+    pub fn escalate_if_needed(&self) -> Result<RunningAs, EscalateError> {
         self.with_env(&[])
     }
 }
@@ -213,7 +1117,7 @@ pub fn builder() -> Escalate {
 /// # }
 /// ```
 #[inline]
-pub fn escalate_if_needed() -> Result<RunningAs, Box<dyn Error>> {
+pub fn escalate_if_needed() -> Result<RunningAs, EscalateError> {
     with_env(&[])
 }
 
@@ -235,10 +1139,33 @@ pub fn escalate_if_needed() -> Result<RunningAs, Box<dyn Error>> {
 /// # }
 /// ```
 #[inline]
-pub fn escalate_with_env() -> Result<RunningAs, Box<dyn Error>> {
+pub fn escalate_with_env() -> Result<RunningAs, EscalateError> {
     with_env_wildcards(&["*"])
 }
 
+/// Restart your program with sudo if the user is not privileged enough, but
+/// fail fast instead of prompting for a password.
+///
+/// Returns [`EscalateError::AuthenticationFailed`] when cached credentials are absent, so a
+/// headless or CI caller can skip gracefully rather than stalling on a TTY
+/// read.
+///
+/// ```
+/// # use std::error::Error;
+/// # fn main() -> Result<(), Box<dyn Error>> {
+/// #   if sudo2::running_as_root() {
+/// if let Err(e) = sudo2::escalate_non_interactive() {
+///     eprintln!("skipping privileged step: {}", e);
+/// }
+/// #   }
+/// #   Ok(())
+/// # }
+/// ```
+#[inline]
+pub fn escalate_non_interactive() -> Result<RunningAs, EscalateError> {
+    Escalate::default().non_interactive().escalate_if_needed()
+}
+
 /// Similar to escalate_if_needed, but with pkexec as the wrapper
 ///
 /// ```
@@ -254,7 +1181,7 @@ pub fn escalate_with_env() -> Result<RunningAs, Box<dyn Error>> {
 /// # }
 /// ```
 #[inline]
-pub fn pkexec() -> Result<RunningAs, Box<dyn Error>> {
+pub fn pkexec() -> Result<RunningAs, EscalateError> {
     builder().wrapper("pkexec").escalate_if_needed()
 }
 
@@ -273,7 +1200,7 @@ pub fn pkexec() -> Result<RunningAs, Box<dyn Error>> {
 /// # }
 /// ```
 #[inline]
-pub fn doas() -> Result<RunningAs, Box<dyn Error>> {
+pub fn doas() -> Result<RunningAs, EscalateError> {
     builder().wrapper("doas").escalate_if_needed()
 }
 
@@ -294,7 +1221,7 @@ pub fn doas() -> Result<RunningAs, Box<dyn Error>> {
 /// #   Ok(())
 /// # }
 /// ```
-pub fn with_env(prefixes: &[&str]) -> Result<RunningAs, Box<dyn Error>> {
+pub fn with_env(prefixes: &[&str]) -> Result<RunningAs, EscalateError> {
     Escalate::default().with_env(prefixes)
 }
 
@@ -318,7 +1245,7 @@ pub fn with_env(prefixes: &[&str]) -> Result<RunningAs, Box<dyn Error>> {
 /// #   Ok(())
 /// # }
 /// ```
-pub fn with_env_wildcards(wildcards: &[&str]) -> Result<RunningAs, Box<dyn Error>> {
+pub fn with_env_wildcards(wildcards: &[&str]) -> Result<RunningAs, EscalateError> {
     Escalate::default().with_env_wildcards(wildcards)
 }
 
@@ -359,4 +1286,97 @@ mod tests {
         assert!(vars.any(|(k, _v)| k == "CARGO_BAR_BAZ"));
         assert!(!vars.any(|(k, _v)| k == "CARGO_FOO_BAR_BAZ"));
     }
+
+    #[test]
+    #[traced_test]
+    fn which_on_path_finds_and_misses() {
+        let dir = std::env::temp_dir().join("sudo2_which_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let bin = dir.join("sudo2-fake-bin");
+        std::fs::write(&bin, b"#!/bin/sh\n").unwrap();
+
+        let orig = std::env::var_os("PATH");
+        let newpath = match &orig {
+            Some(p) => format!("{}:{}", dir.display(), p.to_string_lossy()),
+            None => dir.display().to_string(),
+        };
+        std::env::set_var("PATH", &newpath);
+
+        assert_eq!(which_on_path("sudo2-fake-bin"), Some(bin.clone()));
+        assert!(which_on_path("sudo2-definitely-absent-binary").is_none());
+
+        match orig {
+            Some(p) => std::env::set_var("PATH", p),
+            None => std::env::remove_var("PATH"),
+        }
+        let _ = std::fs::remove_file(&bin);
+    }
+
+    #[test]
+    #[traced_test]
+    fn backend_from_wrapper_maps_known_names() {
+        assert_eq!(Backend::from_wrapper("doas"), Backend::Doas);
+        assert_eq!(Backend::from_wrapper("gsudo"), Backend::Gsudo);
+        assert_eq!(Backend::from_wrapper("pkexec"), Backend::Pkexec);
+        assert_eq!(Backend::from_wrapper("sudo"), Backend::Sudo);
+        // anything unrecognised is treated as sudo-compatible
+        assert_eq!(Backend::from_wrapper("please"), Backend::Sudo);
+    }
+
+    #[test]
+    #[traced_test]
+    fn backend_non_interactive_flags() {
+        assert_eq!(Backend::Sudo.non_interactive_flag(), "-n");
+        assert_eq!(Backend::Doas.non_interactive_flag(), "-n");
+        assert_eq!(Backend::Gsudo.non_interactive_flag(), "-n");
+        assert_eq!(
+            Backend::Pkexec.non_interactive_flag(),
+            "--disable-internal-agent"
+        );
+    }
+
+    #[test]
+    #[traced_test]
+    fn backend_pty_flags() {
+        // Only sudo has a native flag; the rest fall back to an in-process pty.
+        assert_eq!(Backend::Sudo.pty_flag(), Some("--pty"));
+        assert_eq!(Backend::Doas.pty_flag(), None);
+        assert_eq!(Backend::Gsudo.pty_flag(), None);
+        assert_eq!(Backend::Pkexec.pty_flag(), None);
+    }
+
+    #[test]
+    #[traced_test]
+    fn policy_load_parses_rules_and_defaults() {
+        let path = std::env::temp_dir().join("sudo2_policy_test.json");
+        std::fs::write(
+            &path,
+            r#"[
+                { "user": "alice", "runas": "postgres", "command_glob": "/usr/bin/*" },
+                { "user": "bob" }
+            ]"#,
+        )
+        .unwrap();
+
+        let policy = Policy::load(&path).unwrap();
+        assert_eq!(policy.rules.len(), 2);
+        assert_eq!(policy.rules[0].user, "alice");
+        assert_eq!(policy.rules[0].runas, "postgres");
+        assert_eq!(policy.rules[0].command_glob, "/usr/bin/*");
+        // missing fields fall back to the match-anything default
+        assert_eq!(policy.rules[1].user, "bob");
+        assert_eq!(policy.rules[1].runas, "*");
+        assert_eq!(policy.rules[1].command_glob, "*");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[traced_test]
+    fn policy_load_rejects_non_array() {
+        let path = std::env::temp_dir().join("sudo2_policy_bad.json");
+        std::fs::write(&path, r#"{ "user": "alice" }"#).unwrap();
+        assert!(Policy::load(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
 }